@@ -1,28 +1,146 @@
 use ic_cdk::*;
 use ic_cdk::storage;
-use candid::{CandidType, Deserialize};
+use ic_cdk::api::management_canister::main::raw_rand;
+use candid::{CandidType, Deserialize, Principal};
+use std::cell::RefCell;
 use std::collections::HashMap;
-use std::sync::Mutex;
 use bcrypt::{hash, verify};
 
-// Account structure
+// How long a session token stays valid before it must be refreshed via login.
+const SESSION_TTL_NANOS: u64 = 15 * 60 * 1_000_000_000; // 15 minutes
+
+// bcrypt work factor for password hashing. Checked at compile time so it can
+// never regress to the old, far-too-weak cost of 4.
+const BCRYPT_COST: u32 = 12;
+const _: () = assert!(BCRYPT_COST >= 10, "BCRYPT_COST is too weak for real credential storage");
+
+// A plaintext password that zeroes its backing bytes when dropped, so the
+// secret doesn't linger in reusable canister memory pages after use.
+#[derive(CandidType, Deserialize)]
+struct Password(Vec<u8>);
+
+impl Password {
+    fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    // Take ownership of the underlying bytes for a one-shot hash, leaving an
+    // empty (and therefore trivially zeroizable) buffer behind.
+    fn into_bytes(mut self) -> Vec<u8> {
+        std::mem::take(&mut self.0)
+    }
+
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    // Overwrite the backing bytes with zeros in place, without deallocating.
+    // Factored out of `drop` so the zeroing itself is unit-testable.
+    fn zeroize(&mut self) {
+        for byte in self.0.iter_mut() {
+            *byte = 0;
+        }
+    }
+}
+
+impl Drop for Password {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+// Denominations an account is allowed to hold a balance in.
+const SUPPORTED_DENOMINATIONS: &[&str] = &["ICP", "USD"];
+
+fn validate_denom(denom: &str) -> Result<(), String> {
+    if SUPPORTED_DENOMINATIONS.contains(&denom) {
+        Ok(())
+    } else {
+        Err(format!("Unknown denomination: {}", denom))
+    }
+}
+
+// Account structure. Balances are kept per denomination in integer minor
+// units (e.g. ICP e8s, USD cents) to avoid floating-point rounding drift.
 #[derive(Clone, CandidType, Deserialize)]
 struct Account {
     id: String,
     password_hash: String,
-    balance: f64,
+    balances: HashMap<String, u128>,
+}
+
+impl Account {
+    fn balance_of(&self, denom: &str) -> u128 {
+        *self.balances.get(denom).unwrap_or(&0)
+    }
+}
+
+// A login session bound to a single account, identified by the token that is
+// handed to the caller. Tokens rotate on every use, so a leaked token is
+// single-use.
+#[derive(Clone, CandidType, Deserialize)]
+struct Session {
+    account_id: String,
+    expires_at: u64,
+}
+
+// A single completed transfer, kept for auditing.
+#[derive(Clone, CandidType, Deserialize)]
+struct TxRecord {
+    id: u64,
+    from: String,
+    to: String,
+    denom: String,
+    amount: u128,
+    timestamp: u64,
+}
+
+// An append-only record of every transfer the canister has processed.
+#[derive(Clone, CandidType, Deserialize)]
+struct Ledger {
+    records: Vec<TxRecord>,
+    next_id: u64,
+}
+
+impl Ledger {
+    fn new() -> Self {
+        Self {
+            records: Vec::new(),
+            next_id: 0,
+        }
+    }
+
+    fn record(&mut self, from: String, to: String, denom: String, amount: u128) {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.records.push(TxRecord {
+            id,
+            from,
+            to,
+            denom,
+            amount,
+            timestamp: ic_cdk::api::time(),
+        });
+    }
 }
 
 // State to store all accounts
-#[derive(CandidType, Deserialize)]
+#[derive(Clone, CandidType, Deserialize)]
 struct State {
     accounts: HashMap<String, Account>,
+    sessions: HashMap<String, Session>,
+    // Authenticated IC identities, each linked to at most one account.
+    owners: HashMap<Principal, String>,
+    ledger: Ledger,
 }
 
 impl State {
     fn new() -> Self {
         Self {
             accounts: HashMap::new(),
+            sessions: HashMap::new(),
+            owners: HashMap::new(),
+            ledger: Ledger::new(),
         }
     }
 
@@ -32,33 +150,301 @@ impl State {
     }
 }
 
-// Initialize the state storage using IC's storage API
-#[init]
-fn init() {
-    if storage::stable_restore::<(State,)>().is_err() {
-        let state = State::new();
-        storage::stable_save((state,)).expect("Failed to initialize state");
+// Generate a cryptographically random session token using the management
+// canister's randomness endpoint.
+async fn generate_token() -> Result<String, String> {
+    let (rand_bytes,): (Vec<u8>,) = raw_rand()
+        .await
+        .map_err(|(_, msg)| format!("Failed to generate token: {}", msg))?;
+    Ok(rand_bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+// Remove and validate the session behind `token`, returning its account id.
+// The token is consumed unconditionally so a presented token can never be
+// reused, even if the caller's operation later fails.
+fn take_session(state: &mut State, token: &str) -> Result<String, String> {
+    take_session_at(state, token, ic_cdk::api::time())
+}
+
+// Pure core of `take_session`, with `now` injected instead of read from the
+// IC runtime, so the session/expiry logic can be exercised in a test without
+// a canister environment.
+fn take_session_at(state: &mut State, token: &str, now: u64) -> Result<String, String> {
+    let session = state
+        .sessions
+        .remove(token)
+        .ok_or_else(|| "Invalid session token".to_string())?;
+
+    if session.expires_at < now {
+        return Err("Session expired".to_string());
+    }
+
+    Ok(session.account_id)
+}
+
+// Validate the session behind `token` without consuming it, for read-only
+// queries that must not mutate state.
+fn peek_session(state: &State, token: &str) -> Result<String, String> {
+    peek_session_at(state, token, ic_cdk::api::time())
+}
+
+// Pure core of `peek_session`, with `now` injected instead of read from the
+// IC runtime, so it can be exercised in a test without a canister environment.
+fn peek_session_at(state: &State, token: &str, now: u64) -> Result<String, String> {
+    let session = state
+        .sessions
+        .get(token)
+        .ok_or_else(|| "Invalid session token".to_string())?;
+
+    if session.expires_at < now {
+        return Err("Session expired".to_string());
+    }
+
+    Ok(session.account_id.clone())
+}
+
+// Resolve the caller's account in O(1) via the `owners` index, using the
+// password only as a second factor. A caller that isn't linked to an account
+// yet (via `make_account` or `link_principal`) falls back to the original
+// O(n) scan, which matches the password against every account.
+fn resolve_by_caller_or_scan(state: &State, password: &Password) -> Result<String, String> {
+    resolve_by_caller_or_scan_as(state, password, ic_cdk::api::caller())
+}
+
+// Pure core of `resolve_by_caller_or_scan`, with `caller` injected instead of
+// read from the IC runtime, so the owners-index and scan-fallback logic can
+// be exercised in a test without a canister environment.
+fn resolve_by_caller_or_scan_as(state: &State, password: &Password, caller: Principal) -> Result<String, String> {
+    if let Some(account_id) = state.owners.get(&caller) {
+        let acc = state
+            .accounts
+            .get(account_id)
+            .ok_or_else(|| "Account not found".to_string())?;
+        return if verify(password.as_bytes(), &acc.password_hash).unwrap_or(false) {
+            Ok(account_id.clone())
+        } else {
+            Err("Account not found".to_string())
+        };
+    }
+
+    state
+        .accounts
+        .iter()
+        .find(|(_, acc)| verify(password.as_bytes(), &acc.password_hash).unwrap_or(false))
+        .map(|(id, _)| id.clone())
+        .ok_or_else(|| "Account not found".to_string())
+}
+
+// Generate a fresh token and session for `account_id`, ready to be stored.
+// Kept separate from state access since it has to `.await`, and a RefCell
+// borrow must not be held across an await point.
+async fn rotate_session(account_id: String) -> Result<(String, Session), String> {
+    let token = generate_token().await?;
+    let session = Session {
+        account_id,
+        expires_at: ic_cdk::api::time() + SESSION_TTL_NANOS,
+    };
+    Ok((token, session))
+}
+
+// Live canister state, held in heap memory so reads and writes hit it
+// directly instead of round-tripping through stable storage on every call.
+thread_local! {
+    static STATE: RefCell<State> = RefCell::new(State::new());
+}
+
+// Borrow the live state for a read.
+fn with_state<T>(f: impl FnOnce(&State) -> T) -> T {
+    STATE.with(|state| f(&state.borrow()))
+}
+
+// Borrow the live state mutably.
+fn with_state_mut<T>(f: impl FnOnce(&mut State) -> T) -> T {
+    STATE.with(|state| f(&mut state.borrow_mut()))
+}
+
+// Historical on-disk shapes of `State`, oldest first. Candid record decoding
+// only succeeds when every field the *target* type expects is present on the
+// wire (extra wire fields are simply dropped), so a canister upgrading
+// straight from, say, chunk0-1's schema to today's `State` would otherwise
+// trap in post_upgrade the moment `owners`/`ledger` didn't exist yet on disk.
+// `decode_state_bytes` tries the current shape first and works backwards
+// through these so every historical layout this canister has ever written
+// still loads.
+
+// The original, pre-chunk0-3 balance representation.
+#[derive(CandidType, Deserialize)]
+struct AccountV0 {
+    id: String,
+    password_hash: String,
+    balance: f64,
+}
+
+impl From<AccountV0> for Account {
+    fn from(old: AccountV0) -> Self {
+        let mut balances = HashMap::new();
+        balances.insert("ICP".to_string(), old.balance.max(0.0).round() as u128);
+        Account { id: old.id, password_hash: old.password_hash, balances }
+    }
+}
+
+// Pre-chunk0-1: just the accounts map.
+#[derive(CandidType, Deserialize)]
+struct StateV0 {
+    accounts: HashMap<String, AccountV0>,
+}
+
+// chunk0-1: added `sessions`. Balances are still the old single-`f64` shape.
+#[derive(CandidType, Deserialize)]
+struct StateV1 {
+    accounts: HashMap<String, AccountV0>,
+    sessions: HashMap<String, Session>,
+}
+
+impl From<StateV0> for StateV1 {
+    fn from(old: StateV0) -> Self {
+        StateV1 { accounts: old.accounts, sessions: HashMap::new() }
+    }
+}
+
+// chunk0-3: `Account.balance: f64` became `Account.balances: HashMap<String, u128>`.
+#[derive(CandidType, Deserialize)]
+struct StateV2 {
+    accounts: HashMap<String, Account>,
+    sessions: HashMap<String, Session>,
+}
+
+impl From<StateV1> for StateV2 {
+    fn from(old: StateV1) -> Self {
+        StateV2 {
+            accounts: old.accounts.into_iter().map(|(id, acc)| (id, acc.into())).collect(),
+            sessions: old.sessions,
+        }
+    }
+}
+
+// chunk0-5: added `owners`.
+#[derive(CandidType, Deserialize)]
+struct StateV3 {
+    accounts: HashMap<String, Account>,
+    sessions: HashMap<String, Session>,
+    owners: HashMap<Principal, String>,
+}
+
+impl From<StateV2> for StateV3 {
+    fn from(old: StateV2) -> Self {
+        StateV3 { accounts: old.accounts, sessions: old.sessions, owners: HashMap::new() }
     }
 }
 
-// Safely retrieve the state, or create a new state if it doesn't exist
-fn get_state() -> State {
-    match storage::stable_restore::<(State,)>() {
-        Ok((state,)) => state,
-        Err(_) => {
-            ic_cdk::println!("State not found or corrupted. Initializing new state.");
-            State::new() // Return a new state if none exists
+// chunk0-6: added `ledger`, giving today's `State`.
+impl From<StateV3> for State {
+    fn from(old: StateV3) -> Self {
+        State {
+            accounts: old.accounts,
+            sessions: old.sessions,
+            owners: old.owners,
+            ledger: Ledger::new(),
         }
     }
 }
 
-// Save the updated state back to storage
-fn update_state(new_state: State) {
-    storage::stable_save((new_state,)).expect("Failed to save state");
+// Decode stable-memory bytes into the current `State`, migrating forward
+// through every historical shape this canister has written. Tried
+// widest-first (today's `State`), so the first shape that decodes is the
+// widest one whose fields are all present on the wire; a narrower shape
+// would also decode but would drop the newer fields.
+fn decode_state_bytes(bytes: &[u8]) -> Result<State, String> {
+    // Stable memory is read back in whole pages, so the encoded state is
+    // followed by zero padding: decode the leading value and ignore the rest.
+    fn decode<'a, T: CandidType + Deserialize<'a>>(bytes: &'a [u8]) -> Option<T> {
+        candid::de::IDLDeserialize::new(bytes).ok()?.get_value::<T>().ok()
+    }
+
+    if let Some(state) = decode::<State>(bytes) {
+        return Ok(state);
+    }
+    if let Some(v3) = decode::<StateV3>(bytes) {
+        return Ok(v3.into());
+    }
+    if let Some(v2) = decode::<StateV2>(bytes) {
+        return Ok(StateV3::from(v2).into());
+    }
+    if let Some(v1) = decode::<StateV1>(bytes) {
+        return Ok(StateV3::from(StateV2::from(v1)).into());
+    }
+    if let Some(v0) = decode::<StateV0>(bytes) {
+        return Ok(StateV3::from(StateV2::from(StateV1::from(v0))).into());
+    }
+    Err("State corrupted".to_string())
+}
+
+// Load the live state from stable storage, migrating it forward if it was
+// written by an older version of this canister. Refuse to discard an
+// existing-but-unreadable state rather than silently starting over with an
+// empty one.
+fn restore_state_from_stable() {
+    let bytes = ic_cdk::api::stable::stable_bytes();
+    match decode_state_bytes(&bytes) {
+        Ok(state) => with_state_mut(|s| *s = state),
+        Err(_) => ic_cdk::trap("Existing state could not be read; refusing to discard it (State corrupted)"),
+    }
+}
+
+// A freshly installed canister has no stable memory yet, so it's safe to
+// start from the thread_local's empty default State. A reinstall onto a
+// canister that still holds stable memory (e.g. `dfx deploy --mode=reinstall`)
+// must not silently wipe it just because it failed to decode.
+#[init]
+fn init() {
+    if ic_cdk::api::stable::stable_size() > 0 {
+        restore_state_from_stable();
+    }
+}
+
+// Serialize the live state to stable storage once, right before an upgrade
+// discards heap memory.
+#[pre_upgrade]
+fn pre_upgrade() {
+    let state = with_state(|state| state.clone());
+    storage::stable_save((state,)).expect("Failed to save state for upgrade");
+}
+
+// Restore the live state from stable storage once, right after an upgrade.
+#[post_upgrade]
+fn post_upgrade() {
+    restore_state_from_stable();
+}
+
+// Run a multi-step mutation against checkpointed copies of exactly the
+// accounts named in `account_ids` (not the whole canister state, which would
+// mean cloning every account, session and ledger entry on every transfer).
+// Commits the mutated copies in a single pass on success and discards them
+// on failure, so a partial debit/credit is never visible to other calls.
+fn with_account_checkpoint<T, F>(account_ids: &[&str], f: F) -> Result<T, String>
+where
+    F: FnOnce(&mut HashMap<String, Account>) -> Result<T, String>,
+{
+    let mut checkpoint: HashMap<String, Account> = with_state(|state| {
+        account_ids
+            .iter()
+            .filter_map(|id| state.accounts.get(*id).map(|acc| ((*id).to_string(), acc.clone())))
+            .collect()
+    });
+
+    let value = f(&mut checkpoint)?;
+
+    with_state_mut(|state| {
+        for (id, acc) in checkpoint {
+            state.accounts.insert(id, acc);
+        }
+    });
+
+    Ok(value)
 }
 
 // Helper functions for validation
-fn validate_password(password: &String) -> Result<(), String> {
+fn validate_password(password: &Password) -> Result<(), String> {
     if password.len() < 8 {
         Err("Password must be at least 8 characters long".to_string())
     } else {
@@ -66,145 +452,752 @@ fn validate_password(password: &String) -> Result<(), String> {
     }
 }
 
-fn validate_amount(amount: f64) -> Result<(), String> {
-    if amount <= 0.0 { Err("Amount must be greater than 0".to_string()) } else { Ok(()) }
+fn validate_amount(amount: u128) -> Result<(), String> {
+    if amount == 0 { Err("Amount must be greater than 0".to_string()) } else { Ok(()) }
+}
+
+// Render an account's balances as a stable, human-readable list.
+fn format_balances(balances: &HashMap<String, u128>) -> String {
+    let mut entries: Vec<(&String, &u128)> = balances.iter().collect();
+    entries.sort_by_key(|(denom, _)| (*denom).clone());
+    entries
+        .iter()
+        .map(|(denom, amount)| format!("{}: {}", denom, amount))
+        .collect::<Vec<_>>()
+        .join(", ")
 }
 
 // Create a new account
 #[update]
-fn make_account(password: String) -> Result<String, String> {
+fn make_account(password: Password) -> Result<String, String> {
     validate_password(&password)?;
 
-    let mut state = get_state();
     let random_id = State::generate_random_string();
-    let password_hash = hash(&password, 4).map_err(|_| "Failed to hash password".to_string())?;
+    let password_hash = hash(password.into_bytes(), BCRYPT_COST).map_err(|_| "Failed to hash password".to_string())?;
+
+    let mut balances = HashMap::new();
+    balances.insert("ICP".to_string(), 100); // Initial balance
 
     let account = Account {
         id: random_id.clone(),
         password_hash,
-        balance: 100.0, // Initial balance
+        balances,
     };
 
-    state.accounts.insert(random_id.clone(), account);
-    update_state(state);
+    with_state_mut(|state| {
+        state.accounts.insert(random_id.clone(), account);
+        state.owners.insert(ic_cdk::api::caller(), random_id.clone());
+    });
 
     Ok(format!("Account created successfully. ID: {}", random_id))
 }
 
 // Query account info
 #[query]
-fn account_info(password: String) -> Result<String, String> {
-    let state = get_state();
+fn account_info(password: Password) -> Result<String, String> {
+    with_state(|state| {
+        let account_id = resolve_by_caller_or_scan(state, &password)?;
+        let acc = state
+            .accounts
+            .get(&account_id)
+            .ok_or_else(|| "Account not found".to_string())?;
 
-    let account = state.accounts.values().find(|acc| verify(&password, &acc.password_hash).unwrap_or(false));
-    match account {
-        Some(acc) => Ok(format!("Account ID: {}, Balance: {}", acc.id, acc.balance)),
-        None => Err("Account not found".to_string()),
-    }
+        Ok(format!("Account ID: {}, Balances: {}", acc.id, format_balances(&acc.balances)))
+    })
 }
 
 // Check if account exists by destination ID
 #[query]
 fn check_account(dest_id: String) -> Result<String, String> {
-    let state = get_state();
+    with_state(|state| {
+        if state.accounts.contains_key(&dest_id) {
+            Ok("Account exists".to_string())
+        } else {
+            Err("Account does not exist".to_string())
+        }
+    })
+}
 
-    if state.accounts.contains_key(&dest_id) {
-        Ok("Account exists".to_string())
-    } else {
-        Err("Account does not exist".to_string())
-    }
+// Report how many accounts are stored, so operators can confirm state
+// decodes cleanly before issuing any mutating call.
+#[query]
+fn integrity_check() -> Result<u64, String> {
+    Ok(with_state(|state| state.accounts.len() as u64))
 }
 
-// Transfer money between accounts
+// Transfer money between accounts, in a given denomination. The debit and
+// credit run inside a checkpoint of just the two accounts involved, so a
+// failure partway through (e.g. an insufficient balance discovered after the
+// destination check) never leaves a debit without its matching credit.
 #[update]
-fn transfer_money(password: String, amount: f64, dest_id: String) -> Result<String, String> {
-    // Validate the transfer amount
+fn transfer_money(password: Password, amount: u128, dest_id: String, denom: String) -> Result<String, String> {
+    // Validate the transfer amount and denomination
     validate_amount(amount)?;
+    validate_denom(&denom)?;
 
-    let mut state = get_state();
+    // Resolve the source account from the caller's principal, falling back
+    // to a password scan when the caller isn't linked to an account yet.
+    let src_id = with_state(|state| resolve_by_caller_or_scan(state, &password))?;
 
-    // Find the source account's ID by matching the password
-    let source_account_id = state.accounts.iter().find_map(|(id, acc)| {
-        if verify(&password, &acc.password_hash).unwrap_or(false) { Some(id.clone()) } else { None }
-    });
+    if src_id == dest_id {
+        return Err("Cannot transfer to the same account.".to_string());
+    }
 
-    // Check if the destination account exists by its ID
-    let dest_account_exists = state.accounts.contains_key(&dest_id);
+    let new_balance = with_account_checkpoint(&[&src_id, &dest_id], |accounts| {
+        if !accounts.contains_key(&dest_id) {
+            return Err("Destination account not found.".to_string());
+        }
 
-    // Check for detailed errors
-    if source_account_id.is_none() {
-        return Err("Source account not found".to_string());
-    }
+        let new_balance = {
+            let src = accounts.get_mut(&src_id).unwrap();
+            // Check if the source has sufficient balance of the requested denom
+            if src.balance_of(&denom) < amount {
+                return Err("Insufficient balance in source account.".to_string());
+            }
+            // Deduct the amount from the source account
+            *src.balances.entry(denom.clone()).or_insert(0) -= amount;
+            src.balance_of(&denom)
+        };
 
-    if !dest_account_exists {
-        return Err("Destination account not found.".to_string());
-    }
+        {
+            let dest = accounts.get_mut(&dest_id).unwrap();
+            // Add the amount to the destination account
+            *dest.balances.entry(denom.clone()).or_insert(0) += amount;
+        }
 
-    let src_id = source_account_id.unwrap();
+        Ok(new_balance)
+    })?;
 
-    {
-        let src = state.accounts.get_mut(&src_id).unwrap();
-        // Check if the source has sufficient balance
-        if src.balance < amount {
-            return Err("Insufficient balance in source account.".to_string());
+    with_state_mut(|state| state.ledger.record(src_id.clone(), dest_id.clone(), denom.clone(), amount));
+
+    Ok(format!("Successfully transferred {} {}. Source new balance: {}", amount, denom, new_balance))
+}
+
+// Delete account
+#[update]
+fn delete_account(password: Password) -> Result<String, String> {
+    with_state_mut(|state| {
+        let account_id = resolve_by_caller_or_scan(state, &password)?;
+        state.accounts.remove(&account_id);
+        state.owners.retain(|_, id| id != &account_id);
+        Ok("Account deleted successfully".to_string())
+    })
+}
+
+// Update password
+#[update]
+fn update_password(old_password: Password, new_password: Password) -> Result<String, String> {
+    validate_password(&new_password)?;
+    let new_hash = hash(new_password.into_bytes(), BCRYPT_COST).map_err(|_| "Failed to hash password".to_string())?;
+
+    with_state_mut(|state| {
+        let account_id = resolve_by_caller_or_scan(state, &old_password)?;
+        let acc = state
+            .accounts
+            .get_mut(&account_id)
+            .ok_or_else(|| "Account not found".to_string())?;
+        acc.password_hash = new_hash;
+
+        Ok("Password updated successfully".to_string())
+    })
+}
+
+// Attach the caller's principal to an existing password-only account, so
+// future calls can resolve it in O(1) instead of scanning every hash.
+#[update]
+fn link_principal(password: Password) -> Result<String, String> {
+    with_state_mut(|state| {
+        let account_id = state
+            .accounts
+            .iter()
+            .find(|(_, acc)| verify(password.as_bytes(), &acc.password_hash).unwrap_or(false))
+            .map(|(id, _)| id.clone())
+            .ok_or_else(|| "Account not found".to_string())?;
+
+        state.owners.insert(ic_cdk::api::caller(), account_id.clone());
+
+        Ok(format!("Linked caller to account {}", account_id))
+    })
+}
+
+// Move ownership of the caller's account to a different principal. Guarded
+// by the current owner link, not by password, since it's a pure identity
+// change.
+#[update]
+fn transfer_ownership(new_owner: Principal) -> Result<String, String> {
+    with_state_mut(|state| {
+        let caller = ic_cdk::api::caller();
+        let account_id = state
+            .owners
+            .get(&caller)
+            .cloned()
+            .ok_or_else(|| "Caller is not linked to any account".to_string())?;
+
+        state.owners.remove(&caller);
+        state.owners.insert(new_owner, account_id);
+
+        Ok("Ownership transferred successfully".to_string())
+    })
+}
+
+// Exchange a password for a rotating session token, avoiding the need to
+// resend the password (and re-scan every account with bcrypt) on every call.
+#[update]
+async fn login(password: Password) -> Result<String, String> {
+    let account_id = with_state(|state| resolve_by_caller_or_scan(state, &password))?;
+
+    let (token, session) = rotate_session(account_id).await?;
+    with_state_mut(|state| state.sessions.insert(token.clone(), session));
+
+    Ok(token)
+}
+
+// Drop a session, e.g. when the caller is done or the token may be compromised.
+#[update]
+fn logout(token: String) -> Result<String, String> {
+    with_state_mut(|state| {
+        if state.sessions.remove(&token).is_some() {
+            Ok("Logged out successfully".to_string())
+        } else {
+            Err("Session not found".to_string())
         }
-        // Deduct the amount from the source account
-        src.balance -= amount;
-    }
+    })
+}
+
+// Token-based account_info: O(1) session lookup instead of an O(n) bcrypt scan.
+#[update]
+async fn account_info_with_token(token: String) -> Result<(String, String), String> {
+    // Resolve the session without consuming it yet, so a failure below
+    // doesn't burn an otherwise-valid token.
+    let account_id = with_state(|state| peek_session(state, &token))?;
+
+    let message = with_state(|state| {
+        let acc = state
+            .accounts
+            .get(&account_id)
+            .ok_or_else(|| "Account not found".to_string())?;
+        Ok::<String, String>(format!("Account ID: {}, Balances: {}", acc.id, format_balances(&acc.balances)))
+    })?;
 
-    {
-        let dest = state.accounts.get_mut(&dest_id).unwrap();
-        // Add the amount to the destination account
-        dest.balance += amount;
+    // Only now that the operation has actually succeeded do we consume the
+    // presented token and hand back its replacement.
+    with_state_mut(|state| state.sessions.remove(&token));
+    let (new_token, session) = rotate_session(account_id).await?;
+    with_state_mut(|state| state.sessions.insert(new_token.clone(), session));
+
+    Ok((message, new_token))
+}
+
+// Token-based transfer_money: resolves the source account directly from the
+// session instead of scanning every account's password hash. The debit and
+// credit run inside a checkpoint of just the two accounts involved, so a
+// failed transfer never leaves a debit without its matching credit.
+#[update]
+async fn transfer_money_with_token(
+    token: String,
+    amount: u128,
+    dest_id: String,
+    denom: String,
+) -> Result<(String, String), String> {
+    validate_amount(amount)?;
+    validate_denom(&denom)?;
+
+    // Resolve the session without consuming it yet, so an ordinary failure
+    // (bad destination, insufficient balance, ...) doesn't burn the token.
+    let account_id = with_state(|state| peek_session(state, &token))?;
+
+    if account_id == dest_id {
+        return Err("Cannot transfer to the same account.".to_string());
     }
 
-    // Save the source account's new balance before updating the state
-    let new_balance = state.accounts.get(&src_id).unwrap().balance;
+    let new_balance = with_account_checkpoint(&[&account_id, &dest_id], |accounts| {
+        if !accounts.contains_key(&dest_id) {
+            return Err("Destination account not found.".to_string());
+        }
+
+        let new_balance = {
+            let src = accounts
+                .get_mut(&account_id)
+                .ok_or_else(|| "Source account not found".to_string())?;
+            if src.balance_of(&denom) < amount {
+                return Err("Insufficient balance in source account.".to_string());
+            }
+            *src.balances.entry(denom.clone()).or_insert(0) -= amount;
+            src.balance_of(&denom)
+        };
+
+        {
+            let dest = accounts.get_mut(&dest_id).unwrap();
+            *dest.balances.entry(denom.clone()).or_insert(0) += amount;
+        }
+
+        Ok(new_balance)
+    })?;
+
+    with_state_mut(|state| state.ledger.record(account_id.clone(), dest_id.clone(), denom.clone(), amount));
 
-    // Save updated state
-    update_state(state);
+    let message = format!(
+        "Successfully transferred {} {}. Source new balance: {}",
+        amount, denom, new_balance
+    );
 
-    Ok(format!("Successfully transferred {}. Source new balance: {}", amount, new_balance))
+    // Only now that the transfer has actually succeeded do we consume the
+    // presented token and hand back its replacement.
+    with_state_mut(|state| state.sessions.remove(&token));
+    let (new_token, session) = rotate_session(account_id).await?;
+    with_state_mut(|state| state.sessions.insert(new_token.clone(), session));
+
+    Ok((message, new_token))
 }
 
-// Delete account
+// Token-based deposit into one of the account's denominations.
 #[update]
-fn delete_account(password: String) -> Result<String, String> {
-    let mut state = get_state();
+async fn deposit(token: String, denom: String, amount: u128) -> Result<(String, String), String> {
+    validate_amount(amount)?;
+    validate_denom(&denom)?;
 
-    // Find the account's ID by matching the password
-    let account_id = state.accounts
-        .iter()
-        .find(|(_, acc)| verify(&password, &acc.password_hash).unwrap_or(false))
-        .map(|(id, _)| id.clone());
-
-    match account_id {
-        Some(acc_id) => {
-            state.accounts.remove(&acc_id);
-            update_state(state);
-            Ok("Account deleted successfully".to_string())
+    // Resolve the session without consuming it yet, so an ordinary failure
+    // doesn't burn the token.
+    let account_id = with_state(|state| peek_session(state, &token))?;
+
+    let new_balance = with_state_mut(|state| {
+        let acc = state
+            .accounts
+            .get_mut(&account_id)
+            .ok_or_else(|| "Account not found".to_string())?;
+        *acc.balances.entry(denom.clone()).or_insert(0) += amount;
+        Ok::<u128, String>(acc.balance_of(&denom))
+    })?;
+
+    // Only now that the deposit has actually succeeded do we consume the
+    // presented token and hand back its replacement.
+    with_state_mut(|state| state.sessions.remove(&token));
+    let (new_token, session) = rotate_session(account_id).await?;
+    with_state_mut(|state| state.sessions.insert(new_token.clone(), session));
+
+    Ok((
+        format!("Deposited {} {}. New balance: {}", amount, denom, new_balance),
+        new_token,
+    ))
+}
+
+// Token-based withdrawal from one of the account's denominations.
+#[update]
+async fn withdraw(token: String, denom: String, amount: u128) -> Result<(String, String), String> {
+    validate_amount(amount)?;
+    validate_denom(&denom)?;
+
+    // Resolve the session without consuming it yet, so an ordinary failure
+    // (e.g. insufficient balance) doesn't burn the token.
+    let account_id = with_state(|state| peek_session(state, &token))?;
+
+    let new_balance = with_state_mut(|state| {
+        let acc = state
+            .accounts
+            .get_mut(&account_id)
+            .ok_or_else(|| "Account not found".to_string())?;
+        if acc.balance_of(&denom) < amount {
+            return Err("Insufficient balance".to_string());
         }
-        None => Err("Account not found".to_string()),
-    }
+        *acc.balances.entry(denom.clone()).or_insert(0) -= amount;
+        Ok::<u128, String>(acc.balance_of(&denom))
+    })?;
+
+    // Only now that the withdrawal has actually succeeded do we consume the
+    // presented token and hand back its replacement.
+    with_state_mut(|state| state.sessions.remove(&token));
+    let (new_token, session) = rotate_session(account_id).await?;
+    with_state_mut(|state| state.sessions.insert(new_token.clone(), session));
+
+    Ok((
+        format!("Withdrew {} {}. New balance: {}", amount, denom, new_balance),
+        new_token,
+    ))
 }
 
-// Update password
+// Query all of the caller's balances, across every denomination they hold.
+#[query]
+fn all_balances(token: String) -> Result<Vec<(String, u128)>, String> {
+    with_state(|state| {
+        let account_id = peek_session(state, &token)?;
+        let acc = state
+            .accounts
+            .get(&account_id)
+            .ok_or_else(|| "Account not found".to_string())?;
+
+        let mut entries: Vec<(String, u128)> = acc
+            .balances
+            .iter()
+            .map(|(denom, amount)| (denom.clone(), *amount))
+            .collect();
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+        Ok(entries)
+    })
+}
+
+// Query the caller's balance of a single denomination.
+#[query]
+fn balance_of(token: String, denom: String) -> Result<u128, String> {
+    with_state(|state| {
+        let account_id = peek_session(state, &token)?;
+        let acc = state
+            .accounts
+            .get(&account_id)
+            .ok_or_else(|| "Account not found".to_string())?;
+
+        Ok(acc.balance_of(&denom))
+    })
+}
+
+// Query the caller's own transfer history, most recent first.
+#[query]
+fn history(token: String, limit: u64) -> Result<Vec<TxRecord>, String> {
+    with_state(|state| {
+        let account_id = peek_session(state, &token)?;
+
+        Ok(state
+            .ledger
+            .records
+            .iter()
+            .rev()
+            .filter(|record| record.from == account_id || record.to == account_id)
+            .take(limit as usize)
+            .cloned()
+            .collect())
+    })
+}
+
+// Token-based delete_account. The session (and account) cease to exist
+// afterwards, so there is nothing left to rotate a token for.
+#[update]
+fn delete_account_with_token(token: String) -> Result<String, String> {
+    with_state_mut(|state| {
+        let account_id = take_session(state, &token)?;
+        state.accounts.remove(&account_id);
+        state.owners.retain(|_, id| id != &account_id);
+        Ok("Account deleted successfully".to_string())
+    })
+}
+
+// Token-based update_password.
 #[update]
-fn update_password(old_password: String, new_password: String) -> Result<String, String> {
+async fn update_password_with_token(
+    token: String,
+    new_password: Password,
+) -> Result<(String, String), String> {
     validate_password(&new_password)?;
+    let new_hash = hash(new_password.into_bytes(), BCRYPT_COST).map_err(|_| "Failed to hash password".to_string())?;
 
-    let mut state = get_state();
+    // Resolve the session without consuming it yet, so an ordinary failure
+    // doesn't burn the token.
+    let account_id = with_state(|state| peek_session(state, &token))?;
 
-    let account = state.accounts.values_mut().find(|acc| verify(&old_password, &acc.password_hash).unwrap_or(false));
+    with_state_mut(|state| {
+        let acc = state
+            .accounts
+            .get_mut(&account_id)
+            .ok_or_else(|| "Account not found".to_string())?;
+        acc.password_hash = new_hash;
+        Ok::<(), String>(())
+    })?;
 
-    match account {
-        Some(acc) => {
-            acc.password_hash = hash(&new_password, 4).map_err(|_| "Failed to hash password".to_string())?;
-            update_state(state);
-            Ok("Password updated successfully".to_string())
-        }
-        None => Err("Account not found".to_string()),
-    }
+    // Only now that the password has actually been updated do we consume the
+    // presented token and hand back its replacement.
+    with_state_mut(|state| state.sessions.remove(&token));
+    let (new_token, session) = rotate_session(account_id).await?;
+    with_state_mut(|state| state.sessions.insert(new_token.clone(), session));
+
+    Ok(("Password updated successfully".to_string(), new_token))
 }
 
-ic_cdk::export_candid!();
\ No newline at end of file
+ic_cdk::export_candid!();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reset_state() {
+        STATE.with(|state| *state.borrow_mut() = State::new());
+    }
+
+    fn seed_account(id: &str, password: &str) {
+        let password_hash = hash(password.as_bytes(), BCRYPT_COST).unwrap();
+        with_state_mut(|state| {
+            state.accounts.insert(
+                id.to_string(),
+                Account {
+                    id: id.to_string(),
+                    password_hash,
+                    balances: HashMap::new(),
+                },
+            );
+        });
+    }
+
+    fn seed_account_with_balance(id: &str, denom: &str, amount: u128) {
+        with_state_mut(|state| {
+            let mut balances = HashMap::new();
+            balances.insert(denom.to_string(), amount);
+            state.accounts.insert(
+                id.to_string(),
+                Account {
+                    id: id.to_string(),
+                    password_hash: String::new(),
+                    balances,
+                },
+            );
+        });
+    }
+
+    #[test]
+    fn decode_state_bytes_migrates_v0_balance_into_icp_denom() {
+        let mut accounts = HashMap::new();
+        accounts.insert(
+            "alice".to_string(),
+            AccountV0 { id: "alice".to_string(), password_hash: "hash".to_string(), balance: 150.0 },
+        );
+        let bytes = candid::encode_args((StateV0 { accounts },)).unwrap();
+
+        let state = decode_state_bytes(&bytes).unwrap();
+
+        assert_eq!(state.accounts["alice"].balance_of("ICP"), 150);
+        assert!(state.sessions.is_empty());
+        assert!(state.owners.is_empty());
+        assert_eq!(state.ledger.next_id, 0);
+    }
+
+    #[test]
+    fn decode_state_bytes_migrates_v3_by_adding_an_empty_ledger() {
+        let mut balances = HashMap::new();
+        balances.insert("ICP".to_string(), 42);
+        let mut accounts = HashMap::new();
+        accounts.insert("alice".to_string(), Account { id: "alice".to_string(), password_hash: "hash".to_string(), balances });
+        let mut owners = HashMap::new();
+        owners.insert(Principal::anonymous(), "alice".to_string());
+        let bytes = candid::encode_args((StateV3 { accounts, sessions: HashMap::new(), owners },)).unwrap();
+
+        let state = decode_state_bytes(&bytes).unwrap();
+
+        assert_eq!(state.accounts["alice"].balance_of("ICP"), 42);
+        assert_eq!(state.owners.get(&Principal::anonymous()), Some(&"alice".to_string()));
+        assert_eq!(state.ledger.next_id, 0);
+    }
+
+    #[test]
+    fn decode_state_bytes_rejects_undecodable_garbage() {
+        let result = decode_state_bytes(&[0, 1, 2, 3]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn account_checkpoint_commits_both_accounts_on_success() {
+        reset_state();
+        seed_account_with_balance("alice", "ICP", 100);
+        seed_account_with_balance("bob", "ICP", 0);
+
+        let new_balance = with_account_checkpoint(&["alice", "bob"], |accounts| {
+            let src = accounts.get_mut("alice").unwrap();
+            *src.balances.get_mut("ICP").unwrap() -= 40;
+            let new_balance = src.balance_of("ICP");
+
+            let dest = accounts.get_mut("bob").unwrap();
+            *dest.balances.get_mut("ICP").unwrap() += 40;
+
+            Ok(new_balance)
+        });
+
+        assert_eq!(new_balance, Ok(60));
+        with_state(|state| {
+            assert_eq!(state.accounts["alice"].balance_of("ICP"), 60);
+            assert_eq!(state.accounts["bob"].balance_of("ICP"), 40);
+        });
+    }
+
+    #[test]
+    fn account_checkpoint_discards_both_accounts_on_failure() {
+        reset_state();
+        seed_account_with_balance("alice", "ICP", 100);
+        seed_account_with_balance("bob", "ICP", 0);
+
+        let result: Result<(), String> = with_account_checkpoint(&["alice", "bob"], |accounts| {
+            let src = accounts.get_mut("alice").unwrap();
+            *src.balances.get_mut("ICP").unwrap() -= 40;
+            Err("insufficient balance".to_string())
+        });
+
+        assert!(result.is_err());
+        with_state(|state| {
+            assert_eq!(state.accounts["alice"].balance_of("ICP"), 100);
+            assert_eq!(state.accounts["bob"].balance_of("ICP"), 0);
+        });
+    }
+
+    #[test]
+    fn resolve_by_caller_uses_owners_index_over_scan() {
+        reset_state();
+        seed_account("alice", "hunter2");
+        let caller = Principal::anonymous();
+        with_state_mut(|state| {
+            state.owners.insert(caller, "alice".to_string());
+        });
+
+        let password = Password(b"hunter2".to_vec());
+        let resolved = with_state(|state| resolve_by_caller_or_scan_as(state, &password, caller));
+
+        assert_eq!(resolved, Ok("alice".to_string()));
+    }
+
+    #[test]
+    fn resolve_by_caller_falls_back_to_scan_when_unlinked() {
+        reset_state();
+        seed_account("alice", "hunter2");
+
+        let password = Password(b"hunter2".to_vec());
+        let resolved =
+            with_state(|state| resolve_by_caller_or_scan_as(state, &password, Principal::anonymous()));
+
+        assert_eq!(resolved, Ok("alice".to_string()));
+    }
+
+    #[test]
+    fn resolve_by_caller_rejects_wrong_password() {
+        reset_state();
+        seed_account("alice", "hunter2");
+
+        let password = Password(b"wrong-password".to_vec());
+        let resolved =
+            with_state(|state| resolve_by_caller_or_scan_as(state, &password, Principal::anonymous()));
+
+        assert_eq!(resolved, Err("Account not found".to_string()));
+    }
+
+    #[test]
+    fn take_session_at_rejects_unknown_token() {
+        reset_state();
+
+        let result = with_state_mut(|state| take_session_at(state, "missing", 0));
+
+        assert_eq!(result, Err("Invalid session token".to_string()));
+    }
+
+    #[test]
+    fn take_session_at_rejects_expired_session() {
+        reset_state();
+        with_state_mut(|state| {
+            state.sessions.insert(
+                "tok".to_string(),
+                Session { account_id: "alice".to_string(), expires_at: 100 },
+            );
+        });
+
+        let result = with_state_mut(|state| take_session_at(state, "tok", 200));
+
+        assert_eq!(result, Err("Session expired".to_string()));
+    }
+
+    #[test]
+    fn take_session_at_is_single_use() {
+        reset_state();
+        with_state_mut(|state| {
+            state.sessions.insert(
+                "tok".to_string(),
+                Session { account_id: "alice".to_string(), expires_at: 1000 },
+            );
+        });
+
+        let first = with_state_mut(|state| take_session_at(state, "tok", 0));
+        let second = with_state_mut(|state| take_session_at(state, "tok", 0));
+
+        assert_eq!(first, Ok("alice".to_string()));
+        assert_eq!(second, Err("Invalid session token".to_string()));
+    }
+
+    #[test]
+    fn peek_session_at_does_not_consume_the_token() {
+        reset_state();
+        with_state_mut(|state| {
+            state.sessions.insert(
+                "tok".to_string(),
+                Session { account_id: "alice".to_string(), expires_at: 1000 },
+            );
+        });
+
+        let first = with_state(|state| peek_session_at(state, "tok", 0));
+        let second = with_state(|state| peek_session_at(state, "tok", 0));
+
+        assert_eq!(first, Ok("alice".to_string()));
+        assert_eq!(second, Ok("alice".to_string()));
+    }
+
+    #[test]
+    fn validate_denom_accepts_supported_denominations() {
+        assert!(validate_denom("ICP").is_ok());
+        assert!(validate_denom("USD").is_ok());
+    }
+
+    #[test]
+    fn validate_denom_rejects_unknown_denomination() {
+        let result = validate_denom("EUR");
+
+        assert_eq!(result, Err("Unknown denomination: EUR".to_string()));
+    }
+
+    #[test]
+    fn account_balance_of_returns_zero_for_unheld_denom() {
+        let account = Account {
+            id: "alice".to_string(),
+            password_hash: "hash".to_string(),
+            balances: HashMap::new(),
+        };
+
+        assert_eq!(account.balance_of("ICP"), 0);
+    }
+
+    #[test]
+    fn account_balance_of_returns_the_held_amount() {
+        let mut balances = HashMap::new();
+        balances.insert("USD".to_string(), 500);
+        let account = Account { id: "alice".to_string(), password_hash: "hash".to_string(), balances };
+
+        assert_eq!(account.balance_of("USD"), 500);
+        assert_eq!(account.balance_of("ICP"), 0);
+    }
+
+    #[test]
+    fn format_balances_renders_denominations_in_sorted_order() {
+        let mut balances = HashMap::new();
+        balances.insert("USD".to_string(), 500);
+        balances.insert("ICP".to_string(), 150);
+
+        assert_eq!(format_balances(&balances), "ICP: 150, USD: 500");
+    }
+
+    #[test]
+    fn format_balances_renders_empty_balances_as_empty_string() {
+        assert_eq!(format_balances(&HashMap::new()), "");
+    }
+
+    #[test]
+    fn bcrypt_cost_meets_the_minimum_configured_strength() {
+        // Mirrors the compile-time assert next to BCRYPT_COST's definition;
+        // kept here too so the constraint shows up in test output.
+        assert!(BCRYPT_COST >= 10);
+    }
+
+    #[test]
+    fn password_zeroize_overwrites_its_bytes() {
+        let mut password = Password(vec![1, 2, 3, 4]);
+
+        password.zeroize();
+
+        assert_eq!(password.0, vec![0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn password_into_bytes_returns_the_underlying_bytes() {
+        let password = Password(vec![1, 2, 3, 4]);
+
+        assert_eq!(password.into_bytes(), vec![1, 2, 3, 4]);
+    }
+}
\ No newline at end of file